@@ -1,10 +1,17 @@
 use std::{
     env,
     error::Error,
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
+    io::{self, Write},
+    sync::mpsc::{self, SyncSender},
+    thread,
 };
 use csv::ReaderBuilder;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+
+//all amounts are stored as i64 fixed-point values scaled by this factor instead of f32, so that
+//add/subtract never accumulates rounding error and total always equals available + held exactly
+const SCALE: i64 = 10_000;
 
 //helper enum to easily identify which type of transactions we are working with
 #[derive(Clone, Copy, Debug, Deserialize)]
@@ -18,200 +25,572 @@ enum TransactionType {
 }
 
 //this struct will contain all the pertinent information surrounding a transaction we grab from each CSV row
-// NOTE that the amount field is optional since not all transaction types provide an amount 
+// NOTE that the amount field is optional since not all transaction types provide an amount
 #[derive(Copy, Clone, Debug, Deserialize)]
 struct Transaction {
     #[serde(alias = "type")]
     tx_type: TransactionType,
     client: u16,
     tx: u32,
-    amount: Option<f32>,
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    amount: Option<i64>,
+}
+
+//deserializes the raw CSV amount string (e.g. "12.3456") into a fixed-point i64 scaled by SCALE,
+//rejecting anything with more than 4 fractional digits or non-numeric content
+fn deserialize_amount<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw: Option<String> = Option::deserialize(deserializer)?;
+    match raw {
+        None => Ok(None),
+        Some(s) if s.is_empty() => Ok(None),
+        Some(s) => parse_fixed_point(&s)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
+
+//splits on the decimal point and pads the fractional part to exactly 4 digits (a fraction longer
+//than 4 digits is rejected, not truncated), then combines as whole * SCALE + frac
+fn parse_fixed_point(s: &str) -> Result<i64, String> {
+    let s = s.trim();
+    let (negative, unsigned) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+
+    let mut parts = unsigned.splitn(2, '.');
+    let whole_part = parts.next().unwrap_or("");
+    let frac_part = parts.next().unwrap_or("");
+
+    if whole_part.is_empty() || !whole_part.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("\"{}\" is not a valid amount", s));
+    }
+    if !frac_part.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("\"{}\" is not a valid amount", s));
+    }
+    if frac_part.len() > 4 {
+        return Err(format!("\"{}\" has more than 4 fractional digits", s));
+    }
+
+    let whole: i64 = whole_part
+        .parse()
+        .map_err(|_| format!("\"{}\" is not a valid amount", s))?;
+    let mut frac_digits = frac_part.to_string();
+    while frac_digits.len() < 4 {
+        frac_digits.push('0');
+    }
+    let frac: i64 = if frac_digits.is_empty() {
+        0
+    } else {
+        frac_digits.parse().unwrap()
+    };
+
+    let value = whole * SCALE + frac;
+    Ok(if negative { -value } else { value })
+}
+
+//formats a fixed-point amount back into a "whole.frac" string with 4 decimal places, the inverse of parse_fixed_point
+fn format_fixed_point(value: i64) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let value = value.abs();
+    format!("{}{}.{:04}", sign, value / SCALE, value % SCALE)
 }
 
 //this struct will contain all the infromation for a particular client, and we can update their funds as transactions come in
 #[derive(Copy, Clone, Debug)]
 struct Account {
-    available: f32,
-    held: f32,
-    total: f32,
+    available: i64,
+    held: i64,
+    total: i64,
     locked: bool,
-} 
+}
 
-//helper struct to easily record the amount being disputed
+//tracks where a processed transaction sits in its dispute lifecycle. The only legal transitions
+//are Processed -> Disputed, Disputed -> Resolved, and Disputed -> ChargedBack; anything else is a no-op
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum TxState {
+    Processed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+//a processed transaction plus its current dispute state, so a dispute/resolve/chargeback can look up
+//everything it needs (amount, client, state) from one place instead of a separate disputes map
 #[derive(Copy, Clone, Debug)]
-struct DisputeAmt(f32);
+struct StoredTx {
+    tx: Transaction,
+    state: TxState,
+}
 
 //this will help us map client id --> client account infromation. This way, when a transaction comes in, we can easily grab the cient account info
 // in O(1) time then update their account
 #[derive(Clone, Debug)]
 struct ClientList(HashMap<u16, Account>);
 
-//this will help us map both valid transaction id --> Transaction and valid dispute id --> dipute amount
-//This way when we have a dispute, we can find its according transaction in 0(1) time 
-//and when we have a resolution or chargeback of the dispute, we can grab the proper dispute amt in 0(1) time
+//this will help us map valid transaction id --> StoredTx so that when a dispute/resolve/chargeback
+//comes in we can find its transaction, and its current state, in O(1) time
 #[derive(Clone, Debug)]
 struct Transactions {
-    valid: HashMap<u32, Transaction>,
-    disputes: HashMap<u32, DisputeAmt>,
+    valid: HashMap<u32, StoredTx>,
 }
-                  
+
+//every way a handler can reject a row, so a rejection can be logged instead of silently dropped
+#[derive(Copy, Clone, Debug)]
+enum LedgerError {
+    NotEnoughFunds,
+    UnknownTx,
+    AlreadyDisputed,
+    NotDisputed,
+    FrozenAccount,
+    ClientMismatch,
+    MissingAmount,
+}
+
+//one worker's slice of the overall client/transaction state. A transaction's client id always hashes
+//to the same shard, so a shard never needs to see another shard's clients or transactions
+struct Shard {
+    clients: ClientList,
+    transactions: Transactions,
+}
+
+impl Shard {
+    fn new() -> Self {
+        Shard {
+            clients: ClientList(HashMap::new()),
+            transactions: Transactions { valid: HashMap::new() },
+        }
+    }
+
+    //runs a single transaction against this shard's state, logging (rather than aborting on) a rejection
+    fn apply(&mut self, row_number: u64, t: Transaction) {
+        let result = match t.tx_type {
+            TransactionType::Deposit => handle_deposit(t, &mut self.transactions, &mut self.clients),
+            TransactionType::Withdrawal => handle_withdrawal(t, &mut self.transactions, &mut self.clients),
+            TransactionType::Dispute => handle_dispute(t, &mut self.transactions, &mut self.clients),
+            TransactionType::Resolve => handle_resolve(t, &mut self.transactions, &mut self.clients),
+            TransactionType::Chargeback => handle_chargeback(t, &mut self.transactions, &mut self.clients),
+        };
+        if let Err(error) = result {
+            eprintln!("row {}: tx {} rejected: {:?}", row_number, t.tx, error);
+        }
+    }
+}
+
 fn main(){
     //collect the input strings into vector since it makes it easier to work with
     let args: Vec<String> = env::args().collect();
     //make sure that we have only passed in one arg which should be the input file
     assert!(args.len() == 2, "Only arg should be input file in the form \"cargo r -- test.csv\"");
     let input_file = &args[1];
-    //istantiate our transaction and account lists that we will be updated as we parse the CSV
-    let mut clients = ClientList(HashMap::new());
-    let mut transactions = Transactions{ valid:  HashMap::new(), disputes: HashMap::new() };
-    //helper function to prase the CSV and update our data structures accordinlgy
-    match parse_csv(input_file, &mut transactions, &mut clients) {
-        //if nothign went wrong with parsing the input, print the output, otherwise print the error stack
-        Ok(_) => print_client_info(&clients),
+    //shard across one worker per available core, since client accounts only ever partition, never overlap
+    let shard_count = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    //helper function to prase the CSV, dispatch transactions to the worker shards, and merge their output
+    match parse_csv(input_file, shard_count) {
+        //if nothign went wrong with parsing the input, write the output to stdout, otherwise print the error stack
+        //writing to stdout keeps the usual "cargo run -- input.csv > accounts.csv" pipeline working
+        Ok(clients) => {
+            let mut writer = csv::Writer::from_writer(io::stdout());
+            clients.dump_csv(&mut writer).expect("failed to write account output");
+        }
         Err(error) => panic!("Problem parsing the input file: {:?}", error),
     };
 }
 
-fn parse_csv(file_name: &str, transactions: &mut Transactions, clients: &mut ClientList) -> Result<(), Box<dyn Error>> {
+fn parse_csv(file_name: &str, shard_count: usize) -> Result<ClientList, Box<dyn Error>> {
     //create the CSV reader that reads in from the path of the CSV file, set flexible to true since rows could be of uneven length
     let mut reader = ReaderBuilder::new().flexible(true).from_path(file_name)?;
     //create a record object to store each raw record in as we stream from the csv. Allocating only once and overwriting saves time and memory
     let mut raw_record = csv::StringRecord::new();
-    //grab the headers from the csv file to make for easy deserialization 
+    //grab the headers from the csv file to make for easy deserialization
     let mut headers = reader.headers()?.clone();
     //trim headers since we want to account for any potentail raw space
     headers.trim();
-    
-    //stream from the csv reader while there are still rows to process
+
+    //one bounded channel per shard so memory use stays predictable even on a multi-gigabyte input,
+    //and a worker thread per channel that owns a disjoint partition of client state, so no locking
+    //is needed on the hot path
+    let mut senders: Vec<SyncSender<(u64, Transaction)>> = Vec::with_capacity(shard_count);
+    let mut workers = Vec::with_capacity(shard_count);
+    for _ in 0..shard_count {
+        let (sender, receiver) = mpsc::sync_channel::<(u64, Transaction)>(1024);
+        senders.push(sender);
+        workers.push(thread::spawn(move || {
+            let mut shard = Shard::new();
+            for (row_number, t) in receiver {
+                shard.apply(row_number, t);
+            }
+            shard
+        }));
+    }
+
+    //tracks the current row number (1-indexed, header excluded) so a rejection can be pinned to a line in the input
+    let mut row_number: u64 = 0;
+
+    //this thread stays the sole deserializer and just dispatches each record to its shard
     while reader.read_record(&mut raw_record)? {
-        //trim the row to make sure whitesapce is gone  
+        row_number += 1;
+        //trim the row to make sure whitesapce is gone
         raw_record.trim();
-        //deserialize the CSV row into a Transaction   
-        let t: Transaction = raw_record.deserialize(Some(&headers))?;
-
-        //match the trasaction type to execute the proepr logic for parsing each type
-        match t.tx_type {
-            TransactionType::Deposit => handle_deposit(t, transactions, clients),
-            TransactionType::Withdrawal => handle_withdrawal(t, transactions, clients),
-            TransactionType::Dispute => handle_dispute(t, transactions, clients),
-            TransactionType::Resolve => handle_resolve(t, transactions, clients),
-            TransactionType::Chargeback => handle_chargeback(t, transactions, clients)
-        }
+        //deserialize the CSV row into a Transaction. A malformed row is logged and skipped rather
+        //than aborting the whole file, since one bad row shouldn't throw out the rest of the stream
+        let t: Transaction = match raw_record.deserialize(Some(&headers)) {
+            Ok(t) => t,
+            Err(error) => {
+                eprintln!("row {}: could not parse record: {}", row_number, error);
+                continue;
+            }
+        };
+
+        //a client's transactions always hash to the same shard, so disputes/resolves/chargebacks for
+        //that client land in the same place as the deposit/withdrawal they reference, preserving
+        //per-client ordering even though shards run concurrently
+        let shard_index = t.client as usize % shard_count;
+        senders[shard_index].send((row_number, t))?;
     }
-    Ok(())
+
+    //dropping the senders closes each worker's channel, so its receive loop ends once its queue drains
+    drop(senders);
+
+    let mut clients = ClientList(HashMap::new());
+    for worker in workers {
+        let shard = worker.join().expect("shard worker thread panicked");
+        clients.0.extend(shard.clients.0);
+    }
+    Ok(clients)
 }
 
-fn handle_deposit(t: Transaction, transactions: &mut Transactions, clients: &mut ClientList) {
+fn handle_deposit(t: Transaction, transactions: &mut Transactions, clients: &mut ClientList) -> Result<(), LedgerError> {
+    //a deposit row is required to carry an amount -- amount is only Option<i64> because dispute/resolve/
+    //chargeback rows don't have one, so a malformed "deposit,1,1," row must be rejected here rather than unwrapped
+    let amount = t.amount.ok_or(LedgerError::MissingAmount)?;
     //check that the client account already exists, by client ID
     if let Some(client_acc) = clients.0.get_mut(&t.client) {
         //don't process any further if the client account is frozen
-        if client_acc.locked { return }
+        if client_acc.locked { return Err(LedgerError::FrozenAccount) }
         //if the client account does exist, then we update their total funds and their available funds
-        //its ok to unwrap here since this function would only be called on a deposit which is garaunteed to have an amount
-        client_acc.available += t.amount.unwrap();
-        client_acc.total += t.amount.unwrap();
+        client_acc.available += amount;
+        client_acc.total += amount;
     }
     else {
         //If this is a client's first deposit, then we create an account for them and add to the accountlist
         //Their account can be accessed by their client id in the future using the hashmap
         let open_new_acc = Account {
-            available: t.amount.unwrap(),
-            held: 0.0,
-            total: t.amount.unwrap(),
+            available: amount,
+            held: 0,
+            total: amount,
             locked: false,
         };
         clients.0.insert(t.client, open_new_acc);
     }
     //add the transaction to the valid list of transactions that was processed
-    transactions.valid.insert(t.tx, t);
+    transactions.valid.insert(t.tx, StoredTx { tx: t, state: TxState::Processed });
+    Ok(())
 }
 
-fn handle_withdrawal(t: Transaction, transactions: &mut Transactions, clients: &mut ClientList) {
+fn handle_withdrawal(t: Transaction, transactions: &mut Transactions, clients: &mut ClientList) -> Result<(), LedgerError> {
+    //a withdrawal row is required to carry an amount -- reject a malformed "withdrawal,1,2," row here
+    //rather than unwrapping it
+    let amount = t.amount.ok_or(LedgerError::MissingAmount)?;
     //first we check that the client account already exists, since we cannot withdraw from a client that doesn't have an account
-    if let Some(client_acc) = clients.0.get_mut(&t.client) {
-        //don't process any further if the client account is frozen
-        if client_acc.locked { return }
-        //we also need to make sure that the client has as much or more funds than he is trying to withdraw
-        //unwrap() here is ok since withdrawal functions are also garaunteed to have an amount
-        if client_acc.available >= t.amount.unwrap() {
-            //reduce the available and total account funds by the withdrawal amount
-            client_acc.available -= t.amount.unwrap();
-            client_acc.total -= t.amount.unwrap();
-
-            //add the transaction to the valid list since we processed it
-            transactions.valid.insert(t.tx, t);
-        }
-    }
-    //if the client account was not found or the client doesnt have enough funds, we throw out the transaction and do nothing
-    //NOTICE that we do NOT add it to our valid list of transactions either
+    //a client with no account yet has no funds, so we reject the same way as an account with too little available
+    let client_acc = clients.0.get_mut(&t.client).ok_or(LedgerError::NotEnoughFunds)?;
+    //don't process any further if the client account is frozen
+    if client_acc.locked { return Err(LedgerError::FrozenAccount) }
+    //we also need to make sure that the client has as much or more funds than he is trying to withdraw
+    if client_acc.available < amount { return Err(LedgerError::NotEnoughFunds) }
+
+    //reduce the available and total account funds by the withdrawal amount
+    client_acc.available -= amount;
+    client_acc.total -= amount;
+
+    //add the transaction to the valid list since we processed it
+    transactions.valid.insert(t.tx, StoredTx { tx: t, state: TxState::Processed });
+    Ok(())
 }
 
-fn handle_dispute(t: Transaction, transactions: &mut Transactions, clients: &mut ClientList) {
+fn handle_dispute(t: Transaction, transactions: &mut Transactions, clients: &mut ClientList) -> Result<(), LedgerError> {
     //A dispute consists of client either flagging an earlier valid deposit or withdrawal
     //Thus we wanna first make sure that the dispute is referring to a valid past transaction
-    if let Some(past_tx) = transactions.valid.get_mut(&t.tx) {
-        //if the transaction was valid, we can grab the client account associated with the transaction
-        if let Some(client_acc) = clients.0.get_mut(&t.client) {
-            //don't process any further if the client account is frozen
-            if client_acc.locked { return }
-            //now we decrease the client availabe and increase the client hold by the same amount of the disputed transaction
-            client_acc.available -= past_tx.amount.unwrap();
-            client_acc.held += past_tx.amount.unwrap();
-
-            //make a valid dispute object that stores the amount being disuputed for easy access if we ever wanna resolve the dispute
-            let d = DisputeAmt(past_tx.amount.unwrap());
-            //insert the dispute into our map of disputes that can be accessed by dispute tx id
-            transactions.disputes.insert(t.tx, d);
+    let past_tx = transactions.valid.get_mut(&t.tx).ok_or(LedgerError::UnknownTx)?;
+    //that transaction must still be in the Processed state -- a transaction that's already Disputed,
+    //Resolved, or ChargedBack cannot be disputed again
+    if past_tx.state != TxState::Processed { return Err(LedgerError::AlreadyDisputed) }
+    //the dispute must come from the same client the original transaction belongs to, otherwise
+    //a row like "dispute, 99, 1," could move client 1's funds under client 99's lookup
+    if past_tx.tx.client != t.client { return Err(LedgerError::ClientMismatch) }
+
+    //a valid, Processed transaction always has a client account behind it
+    let client_acc = clients.0.get_mut(&t.client).expect("client account must exist for a valid transaction");
+    //don't process any further if the client account is frozen
+    if client_acc.locked { return Err(LedgerError::FrozenAccount) }
+    let amount = past_tx.tx.amount.unwrap();
+    match past_tx.tx.tx_type {
+        //a disputed deposit pulls the disputed amount out of available and into held, since the
+        //deposit itself is what's being called into question
+        TransactionType::Deposit => {
+            client_acc.available -= amount;
+            client_acc.held += amount;
         }
+        //a disputed withdrawal reimburses the client by holding the withdrawn amount back, rather than
+        //subtracting it from available again -- that money already left available when it was withdrawn
+        TransactionType::Withdrawal => {
+            client_acc.held += amount;
+            client_acc.total += amount;
+        }
+        _ => unreachable!("only deposits and withdrawals are ever stored as valid transactions"),
     }
-    //if we are disputing a transaction that doesn't exist or cant find the client account for the transaction, then we do nothing
-    //NOTICE that we do NOT add to the disputes map either
+
+    //the transaction is now Disputed, so a second dispute or an out-of-turn resolve/chargeback is a no-op
+    past_tx.state = TxState::Disputed;
+    Ok(())
 }
 
-fn handle_resolve(t: Transaction, transactions: &mut Transactions, clients: &mut ClientList) {
-    //first we need to make sure that we are trying to resolve a past valid dispute
-    if let Some(past_dispute) = transactions.disputes.get_mut(&t.tx) {
-        //if valid dispute, then we get the clients account info
-        if let Some(client_acc) = clients.0.get_mut(&t.client) {
-            //don't process any further if the client account is frozen
-            if client_acc.locked { return }
-            //now we just need to update their availabe and held using the amt stored in the dispute
-            client_acc.available += past_dispute.0;
-            client_acc.held -= past_dispute.0;
+fn handle_resolve(t: Transaction, transactions: &mut Transactions, clients: &mut ClientList) -> Result<(), LedgerError> {
+    //first we need to make sure that we are trying to resolve a transaction that is currently Disputed
+    let past_tx = transactions.valid.get_mut(&t.tx).ok_or(LedgerError::UnknownTx)?;
+    if past_tx.state != TxState::Disputed { return Err(LedgerError::NotDisputed) }
+    //the resolve must come from the same client that owns the disputed transaction
+    if past_tx.tx.client != t.client { return Err(LedgerError::ClientMismatch) }
 
-            //now since we have resolved the dispute, we can remove it from the map of valid disputes
-            //its ok to unwrap here as well since we have already made sure that the dispute exists in the first place
-            transactions.disputes.remove(&t.tx).unwrap();
+    //a valid, Disputed transaction always has a client account behind it
+    let client_acc = clients.0.get_mut(&t.client).expect("client account must exist for a valid transaction");
+    //don't process any further if the client account is frozen
+    if client_acc.locked { return Err(LedgerError::FrozenAccount) }
+    //resolving means the dispute was found unfounded, so the original transaction stands as processed
+    let amount = past_tx.tx.amount.unwrap();
+    match past_tx.tx.tx_type {
+        //the deposit is confirmed valid, so the held amount simply returns to available
+        TransactionType::Deposit => {
+            client_acc.available += amount;
+            client_acc.held -= amount;
+        }
+        //the withdrawal is confirmed valid too, so it stands -- the hold placed at dispute time is
+        //released back out of total rather than into available, leaving the client exactly where the
+        //withdrawal left them. Giving it to available here would let a client claw back any legitimate
+        //withdrawal on demand via dispute + resolve
+        TransactionType::Withdrawal => {
+            client_acc.held -= amount;
+            client_acc.total -= amount;
         }
+        _ => unreachable!("only deposits and withdrawals are ever stored as valid transactions"),
     }
-    //If we do not recognize a valid dispute to resolve, then we can throw this transaction out and do nothing
+
+    //the dispute is now Resolved, so it cannot be resolved or charged back again
+    past_tx.state = TxState::Resolved;
+    Ok(())
 }
 
-fn handle_chargeback(t: Transaction, transactions: &mut Transactions, clients: &mut ClientList) {
-    //check if the chargeback is referring to a valid past dispute
-    if let Some(past_dispute) = transactions.disputes.get_mut(&t.tx) {
-        //if valid past dispute, get the clients account info
-        if let Some(client_acc) = clients.0.get_mut(&t.client) {
-            //don't process any further if the client account is frozen
-            if client_acc.locked { return }
-            //reduce the clients held and total amt by the disputed amt
-            client_acc.held -= past_dispute.0;
-            client_acc.total -= past_dispute.0;
+fn handle_chargeback(t: Transaction, transactions: &mut Transactions, clients: &mut ClientList) -> Result<(), LedgerError> {
+    //check if the chargeback is referring to a transaction that is currently Disputed
+    let past_tx = transactions.valid.get_mut(&t.tx).ok_or(LedgerError::UnknownTx)?;
+    if past_tx.state != TxState::Disputed { return Err(LedgerError::NotDisputed) }
+    //the chargeback must come from the same client that owns the disputed transaction
+    if past_tx.tx.client != t.client { return Err(LedgerError::ClientMismatch) }
 
-            //mark their account as frozen
-            client_acc.locked = true;
+    //a valid, Disputed transaction always has a client account behind it
+    let client_acc = clients.0.get_mut(&t.client).expect("client account must exist for a valid transaction");
+    //don't process any further if the client account is frozen
+    if client_acc.locked { return Err(LedgerError::FrozenAccount) }
+    let amount = past_tx.tx.amount.unwrap();
+    match past_tx.tx.tx_type {
+        //the deposit is confirmed invalid, so the held amount is removed from the client for good
+        TransactionType::Deposit => {
+            client_acc.held -= amount;
+            client_acc.total -= amount;
+        }
+        //the withdrawal is confirmed invalid (an ATM-style chargeback), so the held amount is released
+        //into available and the client is reimbursed -- unlike a resolve, which lets the withdrawal
+        //stand -- and the account is also frozen pending investigation
+        TransactionType::Withdrawal => {
+            client_acc.available += amount;
+            client_acc.held -= amount;
+        }
+        _ => unreachable!("only deposits and withdrawals are ever stored as valid transactions"),
+    }
+
+    //mark their account as frozen
+    client_acc.locked = true;
+
+    //the transaction is now ChargedBack, so it cannot be disputed, resolved, or charged back again
+    past_tx.state = TxState::ChargedBack;
+    Ok(())
+}
 
-            //remove the disputed transaction
-            transactions.disputes.remove(&t.tx).unwrap();
+impl ClientList {
+    //writes one record per account through the csv crate, sorted by client id via a BTreeMap so
+    //output order is deterministic across runs instead of following HashMap iteration order
+    fn dump_csv<W: Write>(&self, writer: &mut csv::Writer<W>) -> Result<(), Box<dyn Error>> {
+        writer.write_record(["client", "available", "held", "total", "locked"])?;
+
+        let sorted: BTreeMap<u16, Account> = self.0.iter().map(|(&id, &acc)| (id, acc)).collect();
+        for (client_id, account) in sorted {
+            writer.write_record(&[
+                client_id.to_string(),
+                format_fixed_point(account.available),
+                format_fixed_point(account.held),
+                format_fixed_point(account.total),
+                account.locked.to_string(),
+            ])?;
         }
+        writer.flush()?;
+        Ok(())
     }
-    //If the charageback refers to a dispute that does not exist, then simply throw this transactions out
 }
 
-fn print_client_info(clients: &ClientList) {
-    println!("client, available, held, total, locked");
-    //iterate through client accounts map and print pertinent info
-    for (client_id, account)  in clients.0.iter() {
-        println!("{:.4}, {:.4}, {:.4}, {:.4}, {}", client_id, account.available, account.held, account.total, account.locked);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //builds a Transaction from a plain-text amount so tests read like the CSV rows they model
+    fn tx(tx_type: TransactionType, client: u16, tx_id: u32, amount: Option<&str>) -> Transaction {
+        Transaction {
+            tx_type,
+            client,
+            tx: tx_id,
+            amount: amount.map(|a| parse_fixed_point(a).unwrap()),
+        }
+    }
+
+    //runs dump_csv into an in-memory buffer so assertions can check the exact output bytes
+    fn dump(clients: &ClientList) -> String {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        clients.dump_csv(&mut writer).unwrap();
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn disputed_deposit_resolve_returns_held_to_available() {
+        let mut clients = ClientList(HashMap::new());
+        let mut transactions = Transactions { valid: HashMap::new() };
+
+        handle_deposit(tx(TransactionType::Deposit, 1, 1, Some("100.0")), &mut transactions, &mut clients).unwrap();
+        handle_dispute(tx(TransactionType::Dispute, 1, 1, None), &mut transactions, &mut clients).unwrap();
+        handle_resolve(tx(TransactionType::Resolve, 1, 1, None), &mut transactions, &mut clients).unwrap();
+
+        assert_eq!(dump(&clients), "client,available,held,total,locked\n1,100.0000,0.0000,100.0000,false\n");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn dispute_twice_is_rejected_as_already_disputed() {
+        let mut clients = ClientList(HashMap::new());
+        let mut transactions = Transactions { valid: HashMap::new() };
+
+        handle_deposit(tx(TransactionType::Deposit, 1, 1, Some("100.0")), &mut transactions, &mut clients).unwrap();
+        handle_dispute(tx(TransactionType::Dispute, 1, 1, None), &mut transactions, &mut clients).unwrap();
+        let result = handle_dispute(tx(TransactionType::Dispute, 1, 1, None), &mut transactions, &mut clients);
+
+        assert!(matches!(result, Err(LedgerError::AlreadyDisputed)));
+        //the second dispute must be a no-op, so available/held should be unchanged from the first dispute
+        assert_eq!(dump(&clients), "client,available,held,total,locked\n1,0.0000,100.0000,100.0000,false\n");
+    }
+
+    #[test]
+    fn resolve_without_a_prior_dispute_is_rejected_as_not_disputed() {
+        let mut clients = ClientList(HashMap::new());
+        let mut transactions = Transactions { valid: HashMap::new() };
+
+        handle_deposit(tx(TransactionType::Deposit, 1, 1, Some("100.0")), &mut transactions, &mut clients).unwrap();
+        let result = handle_resolve(tx(TransactionType::Resolve, 1, 1, None), &mut transactions, &mut clients);
+
+        assert!(matches!(result, Err(LedgerError::NotDisputed)));
+        assert_eq!(dump(&clients), "client,available,held,total,locked\n1,100.0000,0.0000,100.0000,false\n");
+    }
+
+    #[test]
+    fn chargeback_without_a_prior_dispute_is_rejected_as_not_disputed() {
+        let mut clients = ClientList(HashMap::new());
+        let mut transactions = Transactions { valid: HashMap::new() };
+
+        handle_deposit(tx(TransactionType::Deposit, 1, 1, Some("100.0")), &mut transactions, &mut clients).unwrap();
+        let result = handle_chargeback(tx(TransactionType::Chargeback, 1, 1, None), &mut transactions, &mut clients);
+
+        assert!(matches!(result, Err(LedgerError::NotDisputed)));
+        assert_eq!(dump(&clients), "client,available,held,total,locked\n1,100.0000,0.0000,100.0000,false\n");
+    }
+
+    #[test]
+    fn dispute_from_a_different_client_is_rejected_as_client_mismatch() {
+        let mut clients = ClientList(HashMap::new());
+        let mut transactions = Transactions { valid: HashMap::new() };
+
+        handle_deposit(tx(TransactionType::Deposit, 1, 1, Some("100.0")), &mut transactions, &mut clients).unwrap();
+        //client 99 does not own tx 1, so this must not be able to move client 1's funds
+        let result = handle_dispute(tx(TransactionType::Dispute, 99, 1, None), &mut transactions, &mut clients);
+
+        assert!(matches!(result, Err(LedgerError::ClientMismatch)));
+        assert_eq!(dump(&clients), "client,available,held,total,locked\n1,100.0000,0.0000,100.0000,false\n");
+    }
+
+    //drives the real parse_csv entry point (reader thread + shard workers + end-of-stream merge)
+    //instead of calling handle_* directly, so a regression in the client % N routing or the merge
+    //step would show up here even though every other test bypasses sharding entirely
+    #[test]
+    fn parse_csv_shards_and_merges_multi_client_input() {
+        let path = std::env::temp_dir().join(format!("engine_test_{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "type,client,tx,amount\n\
+             deposit,1,1,100.0\n\
+             deposit,2,2,50.0\n\
+             withdrawal,1,3,40.0\n\
+             dispute,1,3,\n\
+             deposit,3,4,25.0\n",
+        ).unwrap();
+
+        //more shards than clients, so routing and the end-of-stream merge both get exercised
+        let clients = parse_csv(path.to_str().unwrap(), 4).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            dump(&clients),
+            "client,available,held,total,locked\n\
+             1,60.0000,40.0000,100.0000,false\n\
+             2,50.0000,0.0000,50.0000,false\n\
+             3,25.0000,0.0000,25.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn disputed_deposit_chargeback_removes_funds_and_freezes() {
+        let mut clients = ClientList(HashMap::new());
+        let mut transactions = Transactions { valid: HashMap::new() };
+
+        handle_deposit(tx(TransactionType::Deposit, 1, 1, Some("100.0")), &mut transactions, &mut clients).unwrap();
+        handle_dispute(tx(TransactionType::Dispute, 1, 1, None), &mut transactions, &mut clients).unwrap();
+        handle_chargeback(tx(TransactionType::Chargeback, 1, 1, None), &mut transactions, &mut clients).unwrap();
+
+        assert_eq!(dump(&clients), "client,available,held,total,locked\n1,0.0000,0.0000,0.0000,true\n");
+    }
+
+    //regression test for the resolve-reimburses-a-withdrawal bug: resolving a disputed withdrawal must
+    //leave the withdrawal standing, not hand the client their money back
+    #[test]
+    fn disputed_withdrawal_resolve_leaves_withdrawal_standing() {
+        let mut clients = ClientList(HashMap::new());
+        let mut transactions = Transactions { valid: HashMap::new() };
+
+        handle_deposit(tx(TransactionType::Deposit, 1, 1, Some("100.0")), &mut transactions, &mut clients).unwrap();
+        handle_withdrawal(tx(TransactionType::Withdrawal, 1, 2, Some("30.0")), &mut transactions, &mut clients).unwrap();
+        handle_dispute(tx(TransactionType::Dispute, 1, 2, None), &mut transactions, &mut clients).unwrap();
+        handle_resolve(tx(TransactionType::Resolve, 1, 2, None), &mut transactions, &mut clients).unwrap();
+
+        assert_eq!(dump(&clients), "client,available,held,total,locked\n1,70.0000,0.0000,70.0000,false\n");
+    }
+
+    #[test]
+    fn disputed_withdrawal_chargeback_reimburses_and_freezes() {
+        let mut clients = ClientList(HashMap::new());
+        let mut transactions = Transactions { valid: HashMap::new() };
+
+        handle_deposit(tx(TransactionType::Deposit, 1, 1, Some("100.0")), &mut transactions, &mut clients).unwrap();
+        handle_withdrawal(tx(TransactionType::Withdrawal, 1, 2, Some("30.0")), &mut transactions, &mut clients).unwrap();
+        handle_dispute(tx(TransactionType::Dispute, 1, 2, None), &mut transactions, &mut clients).unwrap();
+        handle_chargeback(tx(TransactionType::Chargeback, 1, 2, None), &mut transactions, &mut clients).unwrap();
+
+        assert_eq!(dump(&clients), "client,available,held,total,locked\n1,100.0000,0.0000,100.0000,true\n");
+    }
+}